@@ -0,0 +1,110 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Platform-specific IPC endpoint.
+//!
+//! `nix.rs` drives everything through a `Listener`/`Stream` pair so that
+//! the event loop, framing and connection bookkeeping stay identical on
+//! every platform. On Unix that pair is a thin re-export of
+//! `mio::unix::{UnixListener, UnixStream}`. On Windows it is a named pipe
+//! (`\\.\pipe\...`) wrapped so that it exposes the same `Evented` +
+//! `try_read_buf`/`try_write` surface, the same trick ethers' IPC
+//! transport uses to make a `NamedPipeClient` look like a `UnixStream`.
+
+#[cfg(unix)]
+mod sys {
+	use std::io;
+	use mio::unix::{UnixListener, UnixStream};
+
+	pub type Listener = UnixListener;
+	pub type Stream = UnixStream;
+
+	pub fn bind(addr: &str) -> io::Result<Listener> {
+		UnixListener::bind(addr)
+	}
+
+	pub fn accept(listener: &Listener) -> io::Result<Option<Stream>> {
+		listener.accept()
+	}
+
+	/// Unix endpoints are backed by a socket file that `Server::drop`
+	/// removes; named pipes have no such file to clean up.
+	pub fn cleanup_on_drop() -> bool {
+		true
+	}
+}
+
+#[cfg(windows)]
+mod sys {
+	use std::io;
+	use std::mem;
+	use std::sync::Mutex;
+	use mio::{Evented, EventSet, PollOpt, Selector, Token};
+	use mio_named_pipes::NamedPipe;
+
+	pub type Stream = NamedPipe;
+
+	/// Windows named pipes are one-instance-per-client: once a client
+	/// connects to a pipe instance, that instance *is* the connection and
+	/// can no longer accept anyone else. A real server has to keep a fresh
+	/// waiting instance around and swap it in after every accepted client,
+	/// which is why `Listener` can't just be a `NamedPipe` the way it is on
+	/// Unix; it owns the currently-waiting instance and replaces it in
+	/// `accept` instead.
+	pub struct Listener {
+		addr: String,
+		waiting: Mutex<NamedPipe>,
+	}
+
+	pub fn bind(addr: &str) -> io::Result<Listener> {
+		Ok(Listener {
+			addr: addr.to_owned(),
+			waiting: Mutex::new(NamedPipe::new(addr)?),
+		})
+	}
+
+	pub fn accept(listener: &Listener) -> io::Result<Option<Stream>> {
+		let mut waiting = listener.waiting.lock().unwrap();
+		match waiting.connect() {
+			Ok(()) => {
+				let fresh = NamedPipe::new(&listener.addr)?;
+				Ok(Some(mem::replace(&mut *waiting, fresh)))
+			}
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+			Err(e) => Err(e),
+		}
+	}
+
+	pub fn cleanup_on_drop() -> bool {
+		false
+	}
+
+	impl Evented for Listener {
+		fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+			self.waiting.lock().unwrap().register(selector, token, interest, opts)
+		}
+
+		fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+			self.waiting.lock().unwrap().reregister(selector, token, interest, opts)
+		}
+
+		fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+			self.waiting.lock().unwrap().deregister(selector)
+		}
+	}
+}
+
+pub use self::sys::{Listener, Stream, bind, accept, cleanup_on_drop};
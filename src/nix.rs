@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
-//! jsonrpc server over unix sockets
+//! jsonrpc server over unix sockets (or named pipes on Windows)
 //!
 //! ```no_run
 //! extern crate jsonrpc_core;
@@ -41,7 +41,6 @@
 //! ```
 
 use mio::*;
-use mio::unix::*;
 use bytes::{Buf, ByteBuf, MutByteBuf};
 use std::io;
 use jsonrpc_core::IoHandler;
@@ -49,46 +48,101 @@ use std::sync::*;
 use std::sync::atomic::*;
 use std;
 use slab;
-use validator;
+use serde_json;
+use serde_json::value::RawValue;
+use endpoint::{self, Listener, Stream};
+use subscription::{self, Subscriptions};
+pub use subscription::{Notifier, SubscriptionId};
+use pool::WorkerPool;
+use num_cpus;
 #[cfg(test)]
 use tests;
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 const SERVER: Token = Token(0);
 const MAX_CONCURRENT_CONNECTIONS: usize = 1024;
 const MAX_WRITE_LENGTH: usize = 8192;
 const REQUEST_CHUNK_SIZE: usize = 4096;
+/// Upper bound on a connection's pending (not-yet-complete) request buffer,
+/// so a client that never finishes a JSON value can't grow it unboundedly.
+const MAX_PENDING_REQUEST_SIZE: usize = 8 * 1024 * 1024;
+/// `Handler::Timeout` token for the recurring idle-connection sweep; the
+/// only timeout this server schedules, so a single constant identifies it.
+const IDLE_SWEEP: usize = 0;
+/// Bounds on how often the idle-connection sweep runs; see `idle_sweep_interval_ms`.
+const MIN_IDLE_SWEEP_INTERVAL_MS: u64 = 50;
+const MAX_IDLE_SWEEP_INTERVAL_MS: u64 = 30_000;
+/// Default idle timeout used by `Server::new`/`with_worker_threads`.
+const DEFAULT_IDLE_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+/// How often to run the idle-connection sweep for a given `idle_timeout`:
+/// roughly half the timeout, so a connection isn't kept around noticeably
+/// longer than configured, clamped so a very short timeout (e.g. in tests)
+/// doesn't busy-loop and a very long one doesn't go unswept for ages.
+fn idle_sweep_interval_ms(idle_timeout: Duration) -> u64 {
+	let millis = idle_timeout.as_secs().saturating_mul(1000)
+		+ (idle_timeout.subsec_nanos() / 1_000_000) as u64;
+	(millis / 2).max(MIN_IDLE_SWEEP_INTERVAL_MS).min(MAX_IDLE_SWEEP_INTERVAL_MS)
+}
 
 struct SocketConnection {
-	socket: UnixStream,
+	socket: Stream,
 	write_buf: Option<Vec<u8>>,
+	/// Notifications queued up behind `write_buf` so a subscription push
+	/// doesn't clobber an in-flight response (or another notification).
+	outbound: VecDeque<Vec<u8>>,
 	read_buf: MutByteBuf,
 	token: Option<Token>,
 	interest: EventSet,
 	request: Vec<u8>,
+	last_active: Instant,
+	/// Stamped by `RpcServer::accept` and never changed afterwards. `Token`s
+	/// are recycled once a connection drops, so a response computed for an
+	/// older connection that held this same token is only delivered if its
+	/// generation still matches — see `subscription::Message::Send`.
+	generation: u64,
 }
 
 type Slab<T> = slab::Slab<T, Token>;
 
 impl SocketConnection {
-	fn new(sock: UnixStream) -> Self {
+	fn new(sock: Stream, generation: u64) -> Self {
 		SocketConnection {
 			socket: sock,
 			write_buf: None,
+			outbound: VecDeque::new(),
 			read_buf: ByteBuf::mut_with_capacity(REQUEST_CHUNK_SIZE),
 			token: None,
 			interest: EventSet::hup(),
 			request: Vec::with_capacity(REQUEST_CHUNK_SIZE),
+			last_active: Instant::now(),
+			generation: generation,
+		}
+	}
+
+	/// Queues `payload` for this connection, arming it for writing. Used
+	/// both for normal responses and for server-initiated notifications.
+	fn push_outbound(&mut self, payload: Vec<u8>) {
+		if self.write_buf.is_some() {
+			self.outbound.push_back(payload);
+		} else {
+			self.write_buf = Some(payload);
 		}
+		self.interest.insert(EventSet::writable());
 	}
 
 	fn writable(&mut self, event_loop: &mut EventLoop<RpcServer>, _handler: &IoHandler) -> io::Result<()> {
 		use std::io::Write;
+		self.last_active = Instant::now();
 		if let Some(buf) = self.write_buf.take() {
 			if buf.len() < MAX_WRITE_LENGTH {
 				try!(self.socket.write_all(&buf));
-				self.interest.remove(EventSet::writable());
-				self.interest.insert(EventSet::readable());
+				self.write_buf = self.outbound.pop_front();
+				if self.write_buf.is_none() {
+					self.interest.remove(EventSet::writable());
+					self.interest.insert(EventSet::readable());
+				}
 			} else {
 				try!(self.socket.write_all(&buf[0..MAX_WRITE_LENGTH]));
 				self.write_buf = Some(buf[MAX_WRITE_LENGTH..].to_vec());
@@ -98,32 +152,56 @@ impl SocketConnection {
 		event_loop.reregister(&self.socket, self.token.unwrap(), self.interest, PollOpt::edge() | PollOpt::oneshot())
 	}
 
-	fn readable(&mut self, event_loop: &mut EventLoop<RpcServer>, handler: &IoHandler) -> io::Result<()> {
+	fn readable(&mut self, event_loop: &mut EventLoop<RpcServer>, pool: &WorkerPool) -> io::Result<()> {
+		self.last_active = Instant::now();
 		match self.socket.try_read_buf(&mut self.read_buf) {
 			Ok(None) => {
 				trace!(target: "ipc", "Empty read ({:?})", self.token);
 			}
 			Ok(Some(_)) => {
 				self.request.extend(self.read_buf.bytes());
-				let (requests, last_index) = validator::extract_requests(&self.request);
-				if requests.len() > 0 {
-					let mut response_bytes = Vec::new();
-					for rpc_msg in requests {
-						trace!(target: "ipc", "Request: {}", rpc_msg);
-						let response: Option<String> = handler.handle_request_sync( &rpc_msg);
-						if let Some(response_str) = response {
-							trace!(target: "ipc", "Response: {}", &response_str);
-							response_bytes.extend(response_str.into_bytes());
+
+				if self.request.len() > MAX_PENDING_REQUEST_SIZE {
+					trace!(target: "ipc", "Dropping {:?}: pending request exceeds {} bytes", self.token, MAX_PENDING_REQUEST_SIZE);
+					self.request.clear();
+					self.read_buf.clear();
+					return Err(io::Error::new(io::ErrorKind::InvalidData, "pending request exceeds max size"));
+				}
+
+				let token = self.token.unwrap();
+				let mut consumed = 0;
+				let mut malformed = false;
+				{
+					let mut stream = serde_json::Deserializer::from_slice(&self.request).into_iter::<Box<RawValue>>();
+					while let Some(next) = stream.next() {
+						match next {
+							Ok(value) => {
+								let rpc_msg = value.get().to_owned();
+								trace!(target: "ipc", "Request: {}", rpc_msg);
+								pool.dispatch(token, self.generation, rpc_msg);
+								consumed = stream.byte_offset();
+							}
+							// Not yet a complete JSON value at the end of the
+							// buffer; wait for more bytes on the next read.
+							Err(ref e) if e.is_eof() => break,
+							Err(e) => {
+								trace!(target: "ipc", "Malformed request on {:?}, dropping connection: {:?}", self.token, e);
+								malformed = true;
+								break;
+							}
 						}
 					}
-					self.write_buf = Some(response_bytes);
+				}
 
-					let left_over = self.request.drain(last_index + 1..).collect::<Vec<u8>>();
-					self.request = Vec::with_capacity(REQUEST_CHUNK_SIZE);
-					self.request.extend(&left_over);
+				if malformed {
+					self.request.clear();
+					self.read_buf.clear();
+					return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed JSON-RPC request"));
+				}
 
+				if consumed > 0 {
+					self.request.drain(..consumed);
 					self.interest.remove(EventSet::readable());
-					self.interest.insert(EventSet::writable());
 				} else {
 					self.interest.insert(EventSet::readable());
 					trace!(target: "ipc", "Incomplete request: {}", String::from_utf8(self.request.clone()).unwrap_or("<non-utf>".to_owned()));
@@ -140,10 +218,19 @@ impl SocketConnection {
 }
 
 struct RpcServer {
-	socket: UnixListener,
+	socket: Listener,
 	connections: Slab<SocketConnection>,
 	io_handler: Arc<IoHandler>,
 	tokens: VecDeque<Token>,
+	subscriptions: Arc<Subscriptions>,
+	pool: Arc<WorkerPool>,
+	idle_timeout: Option<Duration>,
+	next_generation: u64,
+	/// Invoked with a connection's token right after it's dropped, so
+	/// application code can clean up any bookkeeping of its own (e.g.
+	/// subscriptions tracked outside of `Subscriptions`) that's keyed by
+	/// token. Set via `Server::on_disconnect`.
+	on_disconnect: Option<Arc<Fn(Token) + Send + Sync>>,
 }
 
 pub struct Server {
@@ -152,6 +239,8 @@ pub struct Server {
 	is_stopping: Arc<AtomicBool>,
 	is_stopped: Arc<AtomicBool>,
 	addr: String,
+	subscriptions: Arc<Subscriptions>,
+	notify_channel: Sender<subscription::Message>,
 }
 
 #[derive(Debug)]
@@ -172,16 +261,49 @@ impl std::convert::From<std::io::Error> for Error {
 impl Server {
 	/// New server
 	pub fn new(socket_addr: &str, io_handler: &Arc<IoHandler>) -> Result<Server, Error> {
-		let (server, event_loop) = try!(RpcServer::start(socket_addr, io_handler));
+		Server::with_worker_threads(socket_addr, io_handler, num_cpus::get())
+	}
+
+	/// New server, running `handle_request_sync` across `worker_threads`
+	/// threads instead of the default of one per available CPU.
+	pub fn with_worker_threads(socket_addr: &str, io_handler: &Arc<IoHandler>, worker_threads: usize) -> Result<Server, Error> {
+		let default_idle_timeout = Some(Duration::from_millis(DEFAULT_IDLE_TIMEOUT_MS));
+		Server::with_options(socket_addr, io_handler, worker_threads, default_idle_timeout)
+	}
+
+	/// New server with full control over the worker-thread count and the
+	/// idle-connection timeout. Pass `None` as `idle_timeout` to keep idle
+	/// connections open indefinitely, e.g. for long-lived subscription
+	/// clients rather than short request/response ones.
+	pub fn with_options(socket_addr: &str, io_handler: &Arc<IoHandler>, worker_threads: usize, idle_timeout: Option<Duration>) -> Result<Server, Error> {
+		let (server, event_loop) = try!(RpcServer::start(socket_addr, io_handler, worker_threads, idle_timeout));
+		let subscriptions = server.subscriptions.clone();
+		let notify_channel = event_loop.channel();
 		Ok(Server {
 			rpc_server: Arc::new(RwLock::new(server)),
 			event_loop: Arc::new(RwLock::new(event_loop)),
 			is_stopping: Arc::new(AtomicBool::new(false)),
 			is_stopped: Arc::new(AtomicBool::new(true)),
 			addr: socket_addr.to_owned(),
+			subscriptions: subscriptions,
+			notify_channel: notify_channel,
 		})
 	}
 
+	/// Handle used to register subscriptions and push server-initiated
+	/// notifications down to connected clients, from any thread.
+	pub fn notifier(&self) -> Notifier {
+		Notifier::new(self.notify_channel.clone(), self.subscriptions.clone())
+	}
+
+	/// Registers `callback` to be run (on the event-loop thread) with a
+	/// connection's token right after it disconnects. `Subscriptions` is
+	/// already purged by the time this runs; this is for application-level
+	/// bookkeeping keyed by token that the server itself doesn't know about.
+	pub fn on_disconnect<F>(&self, callback: F) where F: Fn(Token) + Send + Sync + 'static {
+		self.rpc_server.write().unwrap().on_disconnect = Some(Arc::new(callback));
+	}
+
 	/// Run server (in current thread)
 	pub fn run(&self) {
 		let mut event_loop = self.event_loop.write().unwrap();
@@ -239,29 +361,74 @@ impl Server {
 impl Drop for Server {
 	fn drop(&mut self) {
 		self.stop().unwrap_or_else(|_| {}); // ignore error - can be stopped already
-		::std::fs::remove_file(&self.addr).unwrap_or_else(|_| {}); // ignoer error - server could have never been started
+		if endpoint::cleanup_on_drop() {
+			::std::fs::remove_file(&self.addr).unwrap_or_else(|_| {}); // ignoer error - server could have never been started
+		}
 	}
 }
 
 impl RpcServer {
 	/// start ipc rpc server (blocking)
-	pub fn start(addr: &str, io_handler: &Arc<IoHandler>) -> Result<(RpcServer, EventLoop<RpcServer>), Error> {
+	pub fn start(addr: &str, io_handler: &Arc<IoHandler>, worker_threads: usize, idle_timeout: Option<Duration>) -> Result<(RpcServer, EventLoop<RpcServer>), Error> {
 		let mut event_loop = try!(EventLoop::new());
 		::std::fs::remove_file(addr).unwrap_or_else(|_| {}); // ignore error (if no file)
-		let socket = try!(UnixListener::bind(&addr));
+		let socket = try!(endpoint::bind(addr));
 		event_loop.register(&socket, SERVER, EventSet::readable(), PollOpt::edge()).unwrap();
+		let pool = WorkerPool::new(worker_threads, io_handler.clone(), event_loop.channel());
+		if let Some(idle_timeout) = idle_timeout {
+			event_loop.timeout_ms(IDLE_SWEEP, idle_sweep_interval_ms(idle_timeout))
+				.ok().expect("fatal: could not arm idle-connection timer");
+		}
 		let server = RpcServer {
 			socket: socket,
 			connections: Slab::new_starting_at(Token(1), MAX_CONCURRENT_CONNECTIONS),
 			io_handler: io_handler.clone(),
 			tokens: VecDeque::new(),
+			subscriptions: Arc::new(Subscriptions::new()),
+			pool: Arc::new(pool),
+			idle_timeout: idle_timeout,
+			next_generation: 0,
+			on_disconnect: None,
 		};
 		Ok((server, event_loop))
 	}
 
+	/// Queues `payload` on the connection behind `token`, if it's still
+	/// around, and arms it for writing. `expected_generation` guards against
+	/// delivering a message computed for a now-closed connection to whatever
+	/// later connection was handed the same (recycled) token; pass `None`
+	/// to skip the check when the caller already has a freshly-read token
+	/// (e.g. a broadcast).
+	fn push_to(&mut self, event_loop: &mut EventLoop<RpcServer>, token: Token, expected_generation: Option<u64>, payload: Vec<u8>) {
+		let interest = match self.connections.get_mut(token) {
+			Some(connection) => {
+				if let Some(expected) = expected_generation {
+					if connection.generation != expected {
+						trace!(target: "ipc", "Dropping stale message for {:?} (generation {} != {})", token, expected, connection.generation);
+						return;
+					}
+				}
+				connection.push_outbound(payload);
+				connection.interest
+			}
+			None => return,
+		};
+		event_loop.reregister(&self.connections[token].socket, token, interest, PollOpt::edge() | PollOpt::oneshot())
+			.unwrap_or_else(|_| {});
+	}
+
 	fn accept(&mut self, event_loop: &mut EventLoop<RpcServer>) -> io::Result<()> {
-		let new_client_socket = self.socket.accept().unwrap().unwrap();
-		let connection = SocketConnection::new(new_client_socket);
+		let new_client_socket = endpoint::accept(&self.socket).unwrap().unwrap();
+		// Windows named pipes: `accept` just retired the listening instance
+		// and swapped in a fresh one to keep accepting further clients, so
+		// the event loop needs to be told about it. A harmless no-op on
+		// Unix, where the listening socket never changes identity.
+		event_loop.reregister(&self.socket, SERVER, EventSet::readable(), PollOpt::edge())
+			.ok().expect("fatal: could not re-arm listening socket");
+
+		let generation = self.next_generation;
+		self.next_generation = self.next_generation.wrapping_add(1);
+		let connection = SocketConnection::new(new_client_socket, generation);
 		if self.connections.count() >= MAX_CONCURRENT_CONNECTIONS {
 			// max connections
 			return Ok(());
@@ -283,8 +450,8 @@ impl RpcServer {
 	}
 
 	fn connection_readable(&mut self, event_loop: &mut EventLoop<RpcServer>, tok: Token) -> io::Result<()> {
-		let io_handler = self.io_handler.clone();
-		self.connection(tok).readable(event_loop, &io_handler)
+		let pool = self.pool.clone();
+		self.connection(tok).readable(event_loop, &pool)
 	}
 
 	fn connection_writable(&mut self, event_loop: &mut EventLoop<RpcServer>, tok: Token) -> io::Result<()> {
@@ -298,20 +465,66 @@ impl RpcServer {
 
 	fn drop_connection(&mut self, tok: Token) {
 		trace!(target: "ipc", "Dropping connection {:?}", tok);
-		self.connections.remove(tok);
+		let generation = self.connections.remove(tok).map(|c| c.generation).unwrap_or(0);
+		self.tokens.retain(|t| *t != tok);
+		self.subscriptions.purge(tok);
+		self.pool.purge(tok, generation);
+		if let Some(ref on_disconnect) = self.on_disconnect {
+			on_disconnect(tok);
+		}
 	}
 }
 
 
 impl Handler for RpcServer {
 	type Timeout = usize;
-	type Message = ();
+	type Message = subscription::Message;
+
+	fn notify(&mut self, event_loop: &mut EventLoop<RpcServer>, msg: subscription::Message) {
+		match msg {
+			subscription::Message::Send(token, generation, payload) => {
+				self.push_to(event_loop, token, Some(generation), payload);
+			}
+			subscription::Message::Broadcast(payload) => {
+				let tokens: Vec<Token> = self.tokens.iter().cloned().collect();
+				for token in tokens {
+					self.push_to(event_loop, token, None, payload.clone());
+				}
+			}
+		}
+	}
+
+	fn timeout(&mut self, event_loop: &mut EventLoop<RpcServer>, _timeout: usize) {
+		if let Some(idle_timeout) = self.idle_timeout {
+			let now = Instant::now();
+			let idle: Vec<Token> = self.tokens.iter()
+				.cloned()
+				.filter(|tok| self.connections.get(*tok).map_or(false, |c| now.duration_since(c.last_active) >= idle_timeout))
+				.collect();
+
+			for token in idle {
+				trace!(target: "ipc", "Reaping idle connection {:?}", token);
+				self.drop_connection(token);
+			}
+
+			event_loop.timeout_ms(IDLE_SWEEP, idle_sweep_interval_ms(idle_timeout))
+				.ok().expect("fatal: could not re-arm idle-connection timer");
+		}
+	}
 
 	fn ready(&mut self, event_loop: &mut EventLoop<RpcServer>, token: Token, events: EventSet) {
 		if events.is_readable() {
 			match token {
 				SERVER => self.accept(event_loop).unwrap(),
-				_ => self.connection_readable(event_loop, token).unwrap()
+				_ => {
+					if let Err(e) = self.connection_readable(event_loop, token) {
+						if e.kind() == io::ErrorKind::InvalidData {
+							self.drop_connection(token);
+						} else {
+							panic!("fatal: event loop error reading {:?}: {:?}", token, e);
+						}
+					}
+				}
 			};
 		}
 
@@ -368,3 +581,153 @@ pub fn test_file_removed() {
 	}
 	assert!(::std::fs::metadata(addr).is_err()); // err is file not exists
 }
+
+#[test]
+pub fn test_pipelined_requests_preserve_order_and_braces_in_strings() {
+	use std::io::{Read, Write};
+	use std::os::unix::net::UnixStream;
+
+	let addr = tests::random_ipc_endpoint();
+	let io = tests::dummy_io_handler();
+	let server = Server::new(&addr, &io).unwrap();
+	server.run_async().unwrap();
+	std::thread::sleep(std::time::Duration::from_millis(50));
+
+	// Three requests written in a single batch, with a stray (and
+	// structurally unbalanced) `}` sitting inside a quoted string in the
+	// middle one. A brace-counting extractor would lose track of where
+	// that request ends; the streaming `serde_json::Deserializer` parser
+	// this replaced it with understands JSON string syntax and isn't
+	// fooled by it.
+	let batch = concat!(
+		r#"{"jsonrpc": "2.0", "method": "say_hello", "params": [1, 10], "id": 1}"#,
+		r#"{"jsonrpc": "2.0", "method": "say_hello", "params": [2, 20], "id": 2, "note": "unbalanced } brace in a string"}"#,
+		r#"{"jsonrpc": "2.0", "method": "say_hello", "params": [3, 30], "id": 3}"#
+	);
+	let expected = concat!(
+		r#"{"jsonrpc":"2.0","result":"hello 1! you sent 10","id":1}"#,
+		r#"{"jsonrpc":"2.0","result":"hello 2! you sent 20","id":2}"#,
+		r#"{"jsonrpc":"2.0","result":"hello 3! you sent 30","id":3}"#
+	);
+
+	let mut stream = UnixStream::connect(&addr).unwrap();
+	stream.write_all(batch.as_bytes()).unwrap();
+
+	let mut response = Vec::new();
+	let deadline = std::time::Instant::now() + std::time::Duration::from_millis(500);
+	let mut buf = [0u8; 4096];
+	while response.len() < expected.len() && std::time::Instant::now() < deadline {
+		match stream.read(&mut buf) {
+			Ok(0) => break,
+			Ok(n) => response.extend_from_slice(&buf[..n]),
+			Err(_) => break,
+		}
+	}
+
+	assert_eq!(String::from_utf8(response).unwrap(), expected.to_string());
+}
+
+#[test]
+pub fn test_idle_connection_reaped() {
+	use std::io::Read;
+	use std::os::unix::net::UnixStream;
+
+	let addr = tests::random_ipc_endpoint();
+	let io = tests::dummy_io_handler();
+	let server = Server::with_options(&addr, &io, 1, Some(std::time::Duration::from_millis(50))).unwrap();
+	server.run_async().unwrap();
+	std::thread::sleep(std::time::Duration::from_millis(50));
+
+	let mut stream = UnixStream::connect(&addr).unwrap();
+	stream.set_read_timeout(Some(std::time::Duration::from_millis(1000))).unwrap();
+
+	// Never send a request; just wait for the idle sweep (every ~50ms here —
+	// idle_sweep_interval_ms halves the 50ms timeout but clamps to
+	// MIN_IDLE_SWEEP_INTERVAL_MS, so the halving isn't visible at this
+	// timeout) to reap this connection and expect the server to close its
+	// end.
+	let mut buf = [0u8; 16];
+	let read = stream.read(&mut buf);
+	assert_eq!(read.unwrap(), 0, "idle connection should have been reaped and closed by now");
+}
+
+#[test]
+pub fn test_subscription_push_and_broadcast() {
+	use std::io::{Read, Write};
+	use std::os::unix::net::UnixStream;
+
+	let addr = tests::random_ipc_endpoint();
+	let io = tests::dummy_io_handler();
+	let server = Server::new(&addr, &io).unwrap();
+	server.run_async().unwrap();
+	std::thread::sleep(std::time::Duration::from_millis(50));
+
+	let mut stream = UnixStream::connect(&addr).unwrap();
+	stream.set_read_timeout(Some(std::time::Duration::from_millis(500))).unwrap();
+	// No requests have gone through yet, so this is the server's very first
+	// accepted connection: token 1 (SERVER owns token 0) at generation 0.
+	let token = Token(1);
+	let generation = 0;
+
+	let notifier = server.notifier();
+	let id = notifier.subscribe(token, generation);
+
+	let pushed = notifier.clone();
+	std::thread::spawn(move || {
+		pushed.notify(&id, br#"{"jsonrpc":"2.0","method":"push","params":["one"]}"#.to_vec());
+	});
+
+	let mut response = Vec::new();
+	let mut buf = [0u8; 4096];
+	let n = stream.read(&mut buf).unwrap();
+	response.extend_from_slice(&buf[..n]);
+	assert_eq!(
+		String::from_utf8(response).unwrap(),
+		r#"{"jsonrpc":"2.0","method":"push","params":["one"]}"#
+	);
+
+	// A request/response round-trip on the same connection shouldn't be
+	// disturbed by the earlier push.
+	let request = r#"{"jsonrpc": "2.0", "method": "say_hello", "params": [1, 2], "id": 1}"#;
+	stream.write_all(request.as_bytes()).unwrap();
+	let mut response = Vec::new();
+	let n = stream.read(&mut buf).unwrap();
+	response.extend_from_slice(&buf[..n]);
+	assert_eq!(
+		String::from_utf8(response).unwrap(),
+		r#"{"jsonrpc":"2.0","result":"hello 1! you sent 2","id":1}"#
+	);
+
+	let broadcaster = server.notifier();
+	std::thread::spawn(move || {
+		broadcaster.broadcast(br#"{"jsonrpc":"2.0","method":"push","params":["all"]}"#.to_vec());
+	});
+
+	let mut response = Vec::new();
+	let n = stream.read(&mut buf).unwrap();
+	response.extend_from_slice(&buf[..n]);
+	assert_eq!(
+		String::from_utf8(response).unwrap(),
+		r#"{"jsonrpc":"2.0","method":"push","params":["all"]}"#
+	);
+}
+
+#[test]
+pub fn test_malformed_request_drops_connection() {
+	use std::io::{Read, Write};
+	use std::os::unix::net::UnixStream;
+
+	let addr = tests::random_ipc_endpoint();
+	let io = tests::dummy_io_handler();
+	let server = Server::new(&addr, &io).unwrap();
+	server.run_async().unwrap();
+	std::thread::sleep(std::time::Duration::from_millis(50));
+
+	let mut stream = UnixStream::connect(&addr).unwrap();
+	stream.write_all(b"not json at all").unwrap();
+
+	stream.set_read_timeout(Some(std::time::Duration::from_millis(500))).unwrap();
+	let mut buf = [0u8; 16];
+	let read = stream.read(&mut buf);
+	assert_eq!(read.unwrap(), 0, "connection should be closed after a malformed request");
+}
@@ -0,0 +1,155 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Off-thread execution of `handle_request_sync`.
+//!
+//! `connection_readable` used to call `handle_request_sync` inline on the
+//! event-loop thread, so one method doing blocking I/O or heavy work froze
+//! every other connection. `WorkerPool` hands each extracted JSON-RPC
+//! request to a small pool of threads instead, and the worker posts the
+//! serialized response back to the event loop through the same notify
+//! channel subscriptions use (`subscription::Message::Send`).
+//!
+//! Workers can finish out of order, so responses for a given connection are
+//! held in a small reorder buffer keyed by the request's position in that
+//! connection's stream: a response is only sent on once every response
+//! before it has already gone out, which keeps pipelined requests on one
+//! socket answered in the order the client sent them.
+//!
+//! `Token`s are recycled by `slab` once a connection drops, so a job still
+//! in flight for a connection that's since been closed (dropped, hung up,
+//! or reaped by the idle timer) could otherwise have its response delivered
+//! straight into whatever new connection was handed the same token. Every
+//! job is tagged with the generation `RpcServer` stamped its connection
+//! with at accept time, and `Message::Send` carries that generation along
+//! so the event loop can drop a response whose generation no longer
+//! matches the connection currently sitting at that token instead of
+//! misrouting it.
+//!
+//! `next_seq` and `reorder` are keyed by `(Token, generation)` rather than
+//! bare `Token` for the same reason: if they were keyed by `Token` alone, a
+//! stale job from a dropped connection could still land in a new
+//! connection's fresh reorder state after it reuses the same token (it
+//! gets correctly dropped by `push_to`'s generation check once it reaches
+//! the event loop, but not before it has already advanced `state.next`),
+//! permanently stranding the live connection's real response behind it in
+//! `pending`. Tagging the map key with the generation keeps a stale job
+//! away from the live connection's entry. `purge` additionally drops this module's own per-token-generation
+//! bookkeeping on disconnect so it doesn't grow forever as tokens recycle.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::thread;
+use mio::{Sender, Token};
+use jsonrpc_core::IoHandler;
+use subscription::Message;
+
+struct Job {
+	token: Token,
+	generation: u64,
+	seq: u64,
+	request: String,
+}
+
+#[derive(Default)]
+struct ReorderState {
+	next: u64,
+	pending: BTreeMap<u64, Vec<u8>>,
+}
+
+/// A fixed pool of worker threads that run `IoHandler::handle_request_sync`
+/// off the event loop and feed the responses back onto it.
+pub struct WorkerPool {
+	jobs: mpsc::Sender<Job>,
+	next_seq: Mutex<HashMap<(Token, u64), u64>>,
+	reorder: Arc<Mutex<HashMap<(Token, u64), ReorderState>>>,
+}
+
+impl WorkerPool {
+	/// Spawns `threads` workers (clamped to at least 1) sharing `io_handler`;
+	/// finished responses are posted back through `channel`.
+	pub fn new(threads: usize, io_handler: Arc<IoHandler>, channel: Sender<Message>) -> Self {
+		let (tx, rx) = mpsc::channel::<Job>();
+		let rx = Arc::new(Mutex::new(rx));
+		let reorder: Arc<Mutex<HashMap<(Token, u64), ReorderState>>> = Arc::new(Mutex::new(HashMap::new()));
+
+		for _ in 0..::std::cmp::max(1, threads) {
+			let rx = rx.clone();
+			let io_handler = io_handler.clone();
+			let channel = channel.clone();
+			let reorder = reorder.clone();
+			thread::spawn(move || {
+				loop {
+					let job = match rx.lock().unwrap().recv() {
+						Ok(job) => job,
+						Err(_) => break,
+					};
+
+					trace!(target: "ipc", "Dispatching request from {:?}", job.token);
+					let response = io_handler.handle_request_sync(&job.request).unwrap_or_default();
+
+					// Sent while still holding the lock, so another worker can't
+					// slip a later response of this connection in ahead of these.
+					let mut reorder = reorder.lock().unwrap();
+					let state = match reorder.get_mut(&(job.token, job.generation)) {
+						Some(state) => state,
+						// Purged: the connection has closed, so the response is discarded.
+						None => continue,
+					};
+					state.pending.insert(job.seq, response.into_bytes());
+					while let Some(next_response) = state.pending.remove(&state.next) {
+						let _ = channel.send(Message::Send(job.token, job.generation, next_response));
+						state.next += 1;
+					}
+				}
+			});
+		}
+
+		WorkerPool {
+			jobs: tx,
+			next_seq: Mutex::new(HashMap::new()),
+			reorder: reorder,
+		}
+	}
+
+	/// Enqueues `request` — the raw text of one JSON-RPC value already
+	/// extracted from `token`'s connection, tagged with that connection's
+	/// current `generation` — for off-thread execution.
+	pub fn dispatch(&self, token: Token, generation: u64, request: String) {
+		let seq = {
+			let mut next_seq = self.next_seq.lock().unwrap();
+			let entry = next_seq.entry((token, generation)).or_insert(0);
+			let seq = *entry;
+			*entry += 1;
+			seq
+		};
+		self.reorder.lock().unwrap().entry((token, generation)).or_insert_with(ReorderState::default);
+		let _ = self.jobs.send(Job { token: token, generation: generation, seq: seq, request: request });
+	}
+
+	/// Drops sequencing state kept for `token`'s current `generation`. Called
+	/// when that connection closes so the bookkeeping doesn't grow forever as
+	/// tokens recycle. Keying on the generation (rather than bare `token`)
+	/// means a job still in flight for the *old* generation can't perturb the
+	/// `next_seq`/`reorder` state of whatever new connection reuses this
+	/// token next; when it finishes, the worker finds no `reorder` entry
+	/// under its key and discards the response.
+	pub fn purge(&self, token: Token, generation: u64) {
+		self.next_seq.lock().unwrap().remove(&(token, generation));
+		self.reorder.lock().unwrap().remove(&(token, generation));
+	}
+}
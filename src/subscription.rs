@@ -0,0 +1,146 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Server-initiated notifications.
+//!
+//! `SocketConnection::readable` only ever answers a request that just came
+//! in; subscription-style APIs (`eth_subscribe` and friends) also need to
+//! push unsolicited JSON-RPC notifications down an already-open connection.
+//! `Notifier` is the handle application code keeps around for that: it goes
+//! through mio's `notify` channel so the push can happen off the event-loop
+//! thread, landing in `RpcServer::notify` which queues the payload on the
+//! right `SocketConnection` and arms it for writing.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use mio::{Sender, Token};
+
+/// Identifies one server-initiated notification stream, handed back to the
+/// caller of `Notifier::subscribe` and later passed to `notify`/`unsubscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+/// What gets sent across the mio notify channel.
+pub enum Message {
+	/// Deliver a payload to a single connection, tagged with the generation
+	/// its sender believed that connection was in. `Token`s are recycled by
+	/// `slab` once a connection drops, so the event loop checks this against
+	/// the connection currently sitting at that token and drops the message
+	/// instead of misrouting it to a later, unrelated connection.
+	Send(Token, u64, Vec<u8>),
+	/// Deliver a payload to every connection currently open.
+	Broadcast(Vec<u8>),
+}
+
+/// `SubscriptionId` -> `Token` registry, shared between application code
+/// (registering/dropping subscriptions, and resolving one to its connection
+/// in `Notifier::notify`) and the event loop (which purges a connection's
+/// subscriptions when it closes).
+#[derive(Default)]
+pub struct Subscriptions {
+	next_id: AtomicUsize,
+	owners: Mutex<HashMap<SubscriptionId, (Token, u64)>>,
+	by_token: Mutex<HashMap<Token, Vec<SubscriptionId>>>,
+}
+
+impl Subscriptions {
+	pub fn new() -> Self {
+		Subscriptions {
+			next_id: AtomicUsize::new(0),
+			owners: Mutex::new(HashMap::new()),
+			by_token: Mutex::new(HashMap::new()),
+		}
+	}
+
+	fn create(&self, token: Token, generation: u64) -> SubscriptionId {
+		let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+		self.owners.lock().unwrap().insert(id, (token, generation));
+		self.by_token.lock().unwrap().entry(token).or_insert_with(Vec::new).push(id);
+		id
+	}
+
+	fn remove(&self, id: &SubscriptionId) -> Option<(Token, u64)> {
+		let owner = self.owners.lock().unwrap().remove(id);
+		if let Some((token, _)) = owner {
+			if let Some(ids) = self.by_token.lock().unwrap().get_mut(&token) {
+				ids.retain(|existing| existing != id);
+			}
+		}
+		owner
+	}
+
+	fn owner(&self, id: &SubscriptionId) -> Option<(Token, u64)> {
+		self.owners.lock().unwrap().get(id).cloned()
+	}
+
+	/// Drops every subscription owned by `token`'s connection. Called when
+	/// that connection closes so a later connection that reuses the same
+	/// token doesn't silently inherit its subscriptions.
+	pub fn purge(&self, token: Token) {
+		let ids = self.by_token.lock().unwrap().remove(&token);
+		if let Some(ids) = ids {
+			let mut owners = self.owners.lock().unwrap();
+			for id in ids {
+				owners.remove(&id);
+			}
+		}
+	}
+}
+
+/// Handle application code uses to register subscriptions for a connection
+/// and push notifications down them, from any thread.
+#[derive(Clone)]
+pub struct Notifier {
+	channel: Sender<Message>,
+	subscriptions: Arc<Subscriptions>,
+}
+
+impl Notifier {
+	pub fn new(channel: Sender<Message>, subscriptions: Arc<Subscriptions>) -> Self {
+		Notifier {
+			channel: channel,
+			subscriptions: subscriptions,
+		}
+	}
+
+	/// Registers a new subscription owned by `token`'s connection, currently
+	/// at `generation` (the value `RpcServer` stamped it with at accept
+	/// time). Both are expected to come from whatever per-connection context
+	/// application code already threads through to reach the method handler.
+	pub fn subscribe(&self, token: Token, generation: u64) -> SubscriptionId {
+		self.subscriptions.create(token, generation)
+	}
+
+	/// Drops a subscription; notifications for it are silently ignored
+	/// afterwards instead of erroring.
+	pub fn unsubscribe(&self, id: &SubscriptionId) {
+		self.subscriptions.remove(id);
+	}
+
+	/// Enqueues `payload` for delivery to the connection behind `id`.
+	/// A no-op if the subscription (or its connection) is already gone.
+	pub fn notify(&self, id: &SubscriptionId, payload: Vec<u8>) {
+		if let Some((token, generation)) = self.subscriptions.owner(id) {
+			let _ = self.channel.send(Message::Send(token, generation, payload));
+		}
+	}
+
+	/// Enqueues `payload` for delivery to every connected client.
+	pub fn broadcast(&self, payload: Vec<u8>) {
+		let _ = self.channel.send(Message::Broadcast(payload));
+	}
+}